@@ -1,9 +1,14 @@
 use nannou::osc;
-use std::collections::HashMap;
-use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+pub mod midi;
+pub mod receiver;
+pub mod record;
 
 /// The most recently received state of Jen.
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct State {
     /// The last time a note on was received for each instrument.
     pub note_ons: HashMap<Instrument, Instant>,
@@ -11,17 +16,62 @@ pub struct State {
     pub playhead_bangs: HashMap<Measure, Instant>,
     /// The most recently received playhead position.
     pub playhead_positions: HashMap<Measure, f32>,
+    /// A ring buffer of recent bang times per measure, used to estimate tempo.
+    pub bang_times: HashMap<Measure, VecDeque<Instant>>,
+    /// The most recent playhead position update per measure, used for interpolation.
+    pub playhead_updates: HashMap<Measure, PlayheadUpdate>,
+    /// Recent note-on times per instrument, used for rolling density statistics.
+    pub note_on_times: HashMap<Instrument, VecDeque<Instant>>,
+    /// The window of note-on history retained for rolling density statistics. Entries older than
+    /// this are evicted on update, so it bounds the largest window the statistics can report over.
+    pub activity_window: Duration,
 }
 
-/// Some event emitted by Jen.
+impl Default for State {
+    fn default() -> Self {
+        State {
+            note_ons: HashMap::new(),
+            playhead_bangs: HashMap::new(),
+            playhead_positions: HashMap::new(),
+            bang_times: HashMap::new(),
+            playhead_updates: HashMap::new(),
+            note_on_times: HashMap::new(),
+            activity_window: ACTIVITY_WINDOW,
+        }
+    }
+}
+
+/// The default window over which [`State::activity`] measures note-on density.
+const ACTIVITY_WINDOW: Duration = Duration::from_secs(2);
+
+/// The note-on rate (per second) at which [`State::activity`] saturates to `1.0`.
+const ACTIVITY_MAX_NPS: f32 = 12.0;
+
+/// The most recently received playhead position for a measure, along with the time it arrived and a
+/// rate estimate in position-units-per-second derived from the previous update.
 #[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PlayheadUpdate {
+    /// The received position within `[0.0, 1.0)`.
+    pub position: f32,
+    /// The time at which the position was received.
+    pub time: Instant,
+    /// The estimated rate of change, `None` for the first update and for an update that arrived at
+    /// the same instant as the previous one.
+    pub rate: Option<f32>,
+}
+
+/// The number of recent bang times retained per measure for tempo estimation.
+const BANG_HISTORY: usize = 8;
+
+/// Some event emitted by Jen.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Event {
     NoteOn(Instrument),
     PlayheadBang(Measure),
     PlayheadPosition(Measure, f32),
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Instrument {
     Snare,
     Kick,
@@ -33,7 +83,7 @@ pub enum Instrument {
     Atmos,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Measure {
     Phrase,
     Segment,
@@ -54,22 +104,72 @@ impl State {
         Self::default()
     }
 
+    /// Set the window of note-on history retained for rolling density statistics.
+    ///
+    /// This bounds the largest window that [`notes_per_sec`](Self::notes_per_sec) can report over,
+    /// as older note-on times are evicted on each update.
+    pub fn activity_window(mut self, window: Duration) -> Self {
+        self.activity_window = window;
+        self
+    }
+
     /// Update the jen state via the given events.
+    ///
+    /// The whole batch is stamped with a single `Instant`, so this is only appropriate when the
+    /// events genuinely arrived together. Events pulled from the async [`receiver`] carry their own
+    /// decode-time arrival instants and are applied via [`update_by_timed_events`](Self::update_by_timed_events)
+    /// instead, so that tempo estimation reflects real arrival times even when a render stall drains
+    /// several bangs in one frame.
     pub fn update_by_events<I>(&mut self, events: I)
     where
         I: IntoIterator<Item = Event>,
     {
         let now = Instant::now();
-        for event in events {
+        self.update_by_timed_events(events.into_iter().map(move |event| (now, event)));
+    }
+
+    /// Update the jen state via events paired with the `Instant` at which each one arrived.
+    ///
+    /// Preserving per-event arrival times matters for tempo estimation: if two
+    /// [`Event::PlayheadBang`]s for the same [`Measure`] are drained together, stamping them with a
+    /// single `Instant` would collapse their inter-bang delta to zero and corrupt the median.
+    pub fn update_by_timed_events<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = (Instant, Event)>,
+    {
+        for (now, event) in events {
             match event {
                 Event::NoteOn(inst) => {
                     self.note_ons.insert(inst, now);
+                    let window = self.activity_window;
+                    let times = self.note_on_times.entry(inst).or_default();
+                    times.push_back(now);
+                    evict_older_than(times, now, window);
                 }
                 Event::PlayheadBang(meas) => {
                     self.playhead_bangs.insert(meas, now);
+                    self.record_bang_time(meas, now);
                 }
                 Event::PlayheadPosition(meas, pos) => {
                     self.playhead_positions.insert(meas, pos);
+                    let rate = self.playhead_updates.get(&meas).and_then(|prev| {
+                        let dt = now.saturating_duration_since(prev.time).as_secs_f32();
+                        if dt <= 0.0 {
+                            return None;
+                        }
+                        // Account for the position wrapping back around the bar boundary.
+                        let mut delta = pos - prev.position;
+                        if delta < 0.0 {
+                            delta += 1.0;
+                        }
+                        Some(delta / dt)
+                    });
+                    let update = PlayheadUpdate {
+                        position: pos,
+                        time: now,
+                        rate,
+                    };
+                    self.playhead_updates.insert(meas, update);
                 }
             }
         }
@@ -83,6 +183,15 @@ impl State {
         }
     }
 
+    /// Drain all currently-queued events from the given receiver and apply them in one update.
+    ///
+    /// This decouples network receipt from the frame loop: a slow render cannot stall packet
+    /// handling, as decoding happens on the receiver's own thread.
+    pub fn drain(&mut self, receiver: &receiver::OscReceiver) {
+        let events: Vec<(Instant, Event)> = receiver.try_iter().collect();
+        self.update_by_timed_events(events);
+    }
+
     /// Returns `None` if no events have been received for that instrument.
     pub fn secs_since_note_on(&self, inst: Instrument) -> Option<f64> {
         let now = Instant::now();
@@ -113,6 +222,178 @@ impl State {
     pub fn playhead_position(&self, meas: Measure) -> Option<f32> {
         self.playhead_positions.get(&meas).map(|&f| f)
     }
+
+    /// The playhead position over the given measure, extrapolated forward from the last known
+    /// position using the measured rate of change and wrapped into `[0.0, 1.0)`.
+    ///
+    /// If no update has arrived for longer than a full cycle, or no rate has been established yet,
+    /// this falls back to the raw stored position to avoid runaway drift when playback stops.
+    pub fn interpolated_playhead_position(&self, meas: Measure) -> Option<f32> {
+        let update = self.playhead_updates.get(&meas)?;
+        let rate = match update.rate {
+            Some(rate) if rate > 0.0 => rate,
+            _ => return self.playhead_position(meas),
+        };
+        let elapsed = Instant::now()
+            .saturating_duration_since(update.time)
+            .as_secs_f32();
+        let cycle = 1.0 / rate;
+        if elapsed > cycle {
+            return self.playhead_position(meas);
+        }
+        let pos = update.position + rate * elapsed;
+        Some(pos - pos.floor())
+    }
+
+    /// The rate of note-ons per second for the given instrument over the given window.
+    ///
+    /// `window` is capped at the retained history (see
+    /// [`activity_window`](Self::activity_window), two seconds by default): note-on times older than
+    /// the retained window are evicted on update, so a larger `window` reports over the retained
+    /// history rather than silently inventing a longer one. Widen the retained window with
+    /// [`activity_window`](Self::activity_window) to query over longer spans.
+    pub fn notes_per_sec(&self, inst: Instrument, window: Duration) -> f32 {
+        let now = Instant::now();
+        let window = window.min(self.activity_window);
+        let count = match self.note_on_times.get(&inst) {
+            Some(times) => times
+                .iter()
+                .filter(|&&t| now.saturating_duration_since(t) <= window)
+                .count(),
+            None => 0,
+        };
+        let secs = window.as_secs_f32();
+        if secs > 0.0 {
+            count as f32 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// A `0.0..=1.0` normalized density for the given instrument over the activity window, useful
+    /// for driving the brightness or scale of a visual.
+    pub fn activity(&self, inst: Instrument) -> f32 {
+        (self.notes_per_sec(inst, self.activity_window) / ACTIVITY_MAX_NPS).min(1.0)
+    }
+
+    /// The most active instrument over the activity window, if any note-ons have been received
+    /// within it.
+    pub fn busiest_instrument(&self) -> Option<Instrument> {
+        let now = Instant::now();
+        self.note_on_times
+            .iter()
+            .map(|(&inst, times)| {
+                let recent = times
+                    .iter()
+                    .filter(|&&t| now.saturating_duration_since(t) <= self.activity_window)
+                    .count();
+                (inst, recent)
+            })
+            .filter(|&(_, count)| count > 0)
+            .max_by_key(|&(_, count)| count)
+            .map(|(inst, _)| inst)
+    }
+
+    /// Record a bang time for the given measure, resetting the history if the gap since the last
+    /// bang suggests the tempo has changed or playback has stopped.
+    fn record_bang_time(&mut self, meas: Measure, now: Instant) {
+        if let Some(interval) = self.raw_interval(meas) {
+            if let Some(times) = self.bang_times.get(&meas) {
+                if let Some(&last) = times.back() {
+                    if now.saturating_duration_since(last) > interval * 4 {
+                        self.bang_times.remove(&meas);
+                    }
+                }
+            }
+        }
+        let times = self.bang_times.entry(meas).or_default();
+        times.push_back(now);
+        while times.len() > BANG_HISTORY {
+            times.pop_front();
+        }
+    }
+
+    /// Estimate the interval between successive bangs for the given measure.
+    ///
+    /// Uses the median of the retained inter-bang deltas so that a single dropped or jittered packet
+    /// does not skew the estimate, rejecting any delta more than `1.8×` the median as an outlier.
+    /// Returns `None` until at least two bangs have been retained, and also once the estimate has
+    /// gone stale: if no bang has arrived for more than four intervals (a tempo change or stop), the
+    /// estimate is no longer trusted until the next bang rebuilds the buffer.
+    pub fn estimated_interval(&self, meas: Measure) -> Option<Duration> {
+        let interval = self.raw_interval(meas)?;
+        let &last = self.bang_times.get(&meas)?.back()?;
+        if Instant::now().saturating_duration_since(last) > interval * 4 {
+            return None;
+        }
+        Some(interval)
+    }
+
+    /// The median inter-bang interval from the retained buffer, ignoring staleness.
+    ///
+    /// This is the raw estimate used internally to decide when the buffer should be reset;
+    /// [`estimated_interval`](Self::estimated_interval) layers the staleness check on top for the
+    /// public query path.
+    fn raw_interval(&self, meas: Measure) -> Option<Duration> {
+        let times = self.bang_times.get(&meas)?;
+        if times.len() < 2 {
+            return None;
+        }
+        let mut deltas: Vec<Duration> = times
+            .iter()
+            .zip(times.iter().skip(1))
+            .map(|(&a, &b)| b.saturating_duration_since(a))
+            .collect();
+        let median = median_duration(&mut deltas)?;
+        let mut kept: Vec<Duration> = deltas
+            .into_iter()
+            .filter(|&d| d <= median.mul_f64(1.8))
+            .collect();
+        median_duration(&mut kept)
+    }
+
+    /// Estimate the tempo in beats (bangs) per minute for the given measure.
+    ///
+    /// For [`Measure::Beat`] this is the musical BPM.
+    pub fn estimated_bpm(&self, meas: Measure) -> Option<f64> {
+        self.estimated_interval(meas)
+            .map(|interval| 60.0 / interval.as_secs_f64())
+    }
+
+    /// The estimated number of seconds until the next bang for the given measure, clamped at zero.
+    ///
+    /// Returns `None` if there is no interval estimate or no recorded bang yet.
+    pub fn secs_until_next_bang(&self, meas: Measure) -> Option<f64> {
+        let interval = self.estimated_interval(meas)?.as_secs_f64();
+        let &last = self.bang_times.get(&meas)?.back()?;
+        let since = Instant::now().saturating_duration_since(last).as_secs_f64();
+        Some((interval - since).max(0.0))
+    }
+}
+
+/// Evict any times older than `window` before `now` from the front of the buffer.
+fn evict_older_than(times: &mut VecDeque<Instant>, now: Instant, window: Duration) {
+    while let Some(&front) = times.front() {
+        if now.saturating_duration_since(front) > window {
+            times.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+/// The median of the given durations, sorting the slice in place. `None` if empty.
+fn median_duration(ds: &mut [Duration]) -> Option<Duration> {
+    if ds.is_empty() {
+        return None;
+    }
+    ds.sort_unstable();
+    let mid = ds.len() / 2;
+    if ds.len() % 2 == 0 {
+        Some((ds[mid - 1] + ds[mid]) / 2)
+    } else {
+        Some(ds[mid])
+    }
 }
 
 impl Instrument {
@@ -180,8 +461,11 @@ pub fn osc_msg_to_events(msg: osc::Message) -> Vec<Event> {
                 loop {
                     match iter.next() {
                         Some(osc::Type::Int(i)) if !int_is_mode(i) => {
-                            let inst = Instrument::from_i32(i).expect("unexpected instrument");
-                            events.push(Event::NoteOn(inst));
+                            // Skip out-of-range indices rather than panicking: a malformed packet
+                            // must not take down the background decode thread.
+                            if let Some(inst) = Instrument::from_i32(i) {
+                                events.push(Event::NoteOn(inst));
+                            }
                         }
                         a => {
                             arg = a;
@@ -194,8 +478,10 @@ pub fn osc_msg_to_events(msg: osc::Message) -> Vec<Event> {
                 loop {
                     match iter.next() {
                         Some(osc::Type::Int(i)) if !int_is_mode(i) => {
-                            let measure = Measure::from_i32(i).expect("unexpected measure");
-                            events.push(Event::PlayheadBang(measure));
+                            // Skip out-of-range indices rather than panicking (see note above).
+                            if let Some(measure) = Measure::from_i32(i) {
+                                events.push(Event::PlayheadBang(measure));
+                            }
                         }
                         a => {
                             arg = a;
@@ -227,3 +513,183 @@ pub fn osc_msg_to_events(msg: osc::Message) -> Vec<Event> {
     }
     events
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Install a buffer of bang times for `meas` at the given millisecond offsets from `base`.
+    fn set_bangs(state: &mut State, meas: Measure, base: Instant, offsets_ms: &[u64]) {
+        let times = offsets_ms
+            .iter()
+            .map(|&ms| base + Duration::from_millis(ms))
+            .collect();
+        state.bang_times.insert(meas, times);
+    }
+
+    #[test]
+    fn estimated_bpm_for_known_beat_interval() {
+        let mut state = State::new();
+        set_bangs(&mut state, Measure::Beat, Instant::now(), &[0, 500, 1000]);
+        let bpm = state.estimated_bpm(Measure::Beat).unwrap();
+        assert!((bpm - 120.0).abs() < 1.0, "got {}", bpm);
+    }
+
+    #[test]
+    fn median_rejects_a_single_outlier() {
+        let mut state = State::new();
+        // 500ms deltas with one 5s gap (a dropped packet); the outlier must not skew the estimate.
+        set_bangs(&mut state, Measure::Beat, Instant::now(), &[0, 500, 1000, 6000, 6500]);
+        let interval = state.estimated_interval(Measure::Beat).unwrap();
+        assert!((interval.as_secs_f64() - 0.5).abs() < 0.05, "got {:?}", interval);
+    }
+
+    #[test]
+    fn fewer_than_two_bangs_has_no_estimate() {
+        let mut state = State::new();
+        set_bangs(&mut state, Measure::Beat, Instant::now(), &[0]);
+        assert!(state.estimated_interval(Measure::Beat).is_none());
+        assert!(state.estimated_bpm(Measure::Beat).is_none());
+    }
+
+    #[test]
+    fn estimate_goes_stale_without_recent_bang() {
+        let mut state = State::new();
+        // Three bangs 500ms apart, the last of them ~9s in the past: far more than four intervals.
+        let start = Instant::now()
+            .checked_sub(Duration::from_secs(10))
+            .expect("test host has enough uptime");
+        set_bangs(&mut state, Measure::Beat, start, &[0, 500, 1000]);
+        assert!(state.estimated_interval(Measure::Beat).is_none());
+        assert!(state.secs_until_next_bang(Measure::Beat).is_none());
+    }
+
+    #[test]
+    fn late_bang_resets_the_buffer() {
+        let mut state = State::new();
+        let base = Instant::now();
+        set_bangs(&mut state, Measure::Beat, base, &[0, 500, 1000]);
+        // A bang arriving more than four 500ms intervals after the last one resets the buffer.
+        state.record_bang_time(Measure::Beat, base + Duration::from_millis(4000));
+        assert_eq!(state.bang_times[&Measure::Beat].len(), 1);
+    }
+
+    #[test]
+    fn coincident_bangs_do_not_collapse_the_estimate() {
+        // Three bangs drained in one frame but carrying their true arrival instants keep a real delta.
+        let mut state = State::new();
+        let base = Instant::now();
+        state.update_by_timed_events([0u64, 500, 1000].iter().map(|&ms| {
+            (
+                base + Duration::from_millis(ms),
+                Event::PlayheadBang(Measure::Beat),
+            )
+        }));
+        let interval = state.estimated_interval(Measure::Beat).unwrap();
+        assert!((interval.as_secs_f64() - 0.5).abs() < 0.05, "got {:?}", interval);
+    }
+
+    #[test]
+    fn osc_msg_skips_out_of_range_instrument() {
+        // Mode `100` (note-on), an out-of-range index `8`, then a valid `1` (Kick). The malformed
+        // index must be skipped, not panic — the background decode thread relies on this.
+        let msg = osc::Message {
+            addr: "/jen".to_string(),
+            args: Some(vec![
+                osc::Type::Int(NOTE_ON),
+                osc::Type::Int(8),
+                osc::Type::Int(1),
+            ]),
+        };
+        assert_eq!(osc_msg_to_events(msg), vec![Event::NoteOn(Instrument::Kick)]);
+    }
+
+    #[test]
+    fn interpolation_advances_and_wraps_past_the_bar() {
+        let mut state = State::new();
+        let time = Instant::now()
+            .checked_sub(Duration::from_millis(100))
+            .expect("test host has enough uptime");
+        // rate 1.0 pos/sec, last pos 0.95, ~0.1s elapsed -> ~1.05 which wraps into [0.0, 1.0).
+        state.playhead_updates.insert(
+            Measure::Bar,
+            PlayheadUpdate {
+                position: 0.95,
+                time,
+                rate: Some(1.0),
+            },
+        );
+        state.playhead_positions.insert(Measure::Bar, 0.95);
+        let pos = state.interpolated_playhead_position(Measure::Bar).unwrap();
+        assert!((0.0..1.0).contains(&pos), "out of range: {}", pos);
+        assert!((pos - 0.05).abs() < 0.02, "got {}", pos);
+    }
+
+    #[test]
+    fn interpolation_falls_back_after_one_cycle() {
+        let mut state = State::new();
+        let time = Instant::now()
+            .checked_sub(Duration::from_secs(5))
+            .expect("test host has enough uptime");
+        // rate 1.0 -> one cycle is 1s; 5s without an update falls back to the raw stored position.
+        state.playhead_updates.insert(
+            Measure::Bar,
+            PlayheadUpdate {
+                position: 0.3,
+                time,
+                rate: Some(1.0),
+            },
+        );
+        state.playhead_positions.insert(Measure::Bar, 0.3);
+        assert_eq!(state.interpolated_playhead_position(Measure::Bar), Some(0.3));
+    }
+
+    #[test]
+    fn interpolation_is_none_without_an_update() {
+        assert!(State::new().interpolated_playhead_position(Measure::Bar).is_none());
+    }
+
+    /// Install note-on times for `inst` at the given whole-second ages before `now`.
+    fn set_note_ons_secs_ago(state: &mut State, inst: Instrument, now: Instant, secs_ago: &[u64]) {
+        let times = secs_ago
+            .iter()
+            .map(|&s| {
+                now.checked_sub(Duration::from_secs(s))
+                    .expect("test host has enough uptime")
+            })
+            .collect();
+        state.note_on_times.insert(inst, times);
+    }
+
+    #[test]
+    fn notes_per_sec_honours_a_wider_configured_window() {
+        // Four note-ons between 5 and 8 seconds ago: the default 2s window sees none, while a 10s
+        // retained window sees all four. This proves `window` is a real knob, not a silent 2s cap.
+        let mut state = State::new().activity_window(Duration::from_secs(10));
+        let now = Instant::now();
+        set_note_ons_secs_ago(&mut state, Instrument::Snare, now, &[5, 6, 7, 8]);
+        assert_eq!(state.notes_per_sec(Instrument::Snare, Duration::from_secs(2)), 0.0);
+        let nps = state.notes_per_sec(Instrument::Snare, Duration::from_secs(10));
+        assert!((nps - 0.4).abs() < 0.01, "got {}", nps);
+    }
+
+    #[test]
+    fn busiest_instrument_picks_the_most_active() {
+        let mut state = State::new();
+        let now = Instant::now();
+        set_note_ons_secs_ago(&mut state, Instrument::Kick, now, &[0, 1]);
+        set_note_ons_secs_ago(&mut state, Instrument::Snare, now, &[0, 1]);
+        // Give the snare an extra recent hit so it wins the window.
+        state
+            .note_on_times
+            .get_mut(&Instrument::Snare)
+            .unwrap()
+            .push_back(now);
+        assert_eq!(state.busiest_instrument(), Some(Instrument::Snare));
+    }
+
+    #[test]
+    fn busiest_instrument_is_none_when_idle() {
+        assert!(State::new().busiest_instrument().is_none());
+    }
+}
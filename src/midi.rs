@@ -0,0 +1,322 @@
+//! A MIDI output bridge that re-emits Jen [`Event`]s as MIDI.
+//!
+//! [`MidiBridge`] opens a (virtual, where supported) MIDI output port via `midir` and translates
+//! each [`Event::NoteOn`] into a note-on/note-off pair on a configurable channel, using a
+//! per-[`Instrument`] note mapping and a short gate length. [`Event::PlayheadBang`] on
+//! [`Measure::Quaver`] is translated into a MIDI timing-clock message so downstream gear can sync.
+//!
+//! The translation itself lives in [`event_to_messages`] so it can be exercised without real
+//! hardware.
+
+use crate::{Event, Instrument, Measure};
+use midir::{MidiOutput, MidiOutputConnection};
+#[cfg(unix)]
+use midir::os::unix::VirtualOutput;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The default per-instrument note numbers, indexed to match [`Instrument::from_i32`].
+pub const DEFAULT_NOTE_MAP: [u8; Instrument::TOTAL_VARIANTS] = [
+    38, // Snare
+    36, // Kick
+    51, // Ride
+    40, // Ghost
+    43, // Bass
+    60, // Melodic
+    48, // Chordal
+    72, // Atmos
+];
+
+/// The velocity used for note-on messages.
+const VELOCITY: u8 = 100;
+
+/// The default gate length held between a note-on and its note-off.
+const DEFAULT_GATE: Duration = Duration::from_millis(100);
+
+/// A single MIDI message ready to be written to an output port.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MidiMessage {
+    NoteOn { channel: u8, note: u8, velocity: u8 },
+    NoteOff { channel: u8, note: u8 },
+    /// MIDI timing clock (`0xF8`).
+    Clock,
+}
+
+impl MidiMessage {
+    /// The raw bytes of the message.
+    pub fn bytes(&self) -> Vec<u8> {
+        match *self {
+            MidiMessage::NoteOn {
+                channel,
+                note,
+                velocity,
+            } => vec![0x90 | (channel & 0x0F), note, velocity],
+            MidiMessage::NoteOff { channel, note } => vec![0x80 | (channel & 0x0F), note, 0],
+            MidiMessage::Clock => vec![0xF8],
+        }
+    }
+}
+
+/// The index of the instrument within [`DEFAULT_NOTE_MAP`].
+fn note_index(inst: Instrument) -> usize {
+    match inst {
+        Instrument::Snare => 0,
+        Instrument::Kick => 1,
+        Instrument::Ride => 2,
+        Instrument::Ghost => 3,
+        Instrument::Bass => 4,
+        Instrument::Melodic => 5,
+        Instrument::Chordal => 6,
+        Instrument::Atmos => 7,
+    }
+}
+
+/// Translate a single event into the MIDI messages it produces.
+///
+/// A [`Event::NoteOn`] yields a note-on followed by the matching note-off (the caller is
+/// responsible for the gate between them). A [`Event::PlayheadBang`] on [`Measure::Quaver`] yields a
+/// timing clock. All other events yield nothing.
+pub fn event_to_messages(
+    event: Event,
+    channel: u8,
+    note_map: &[u8; Instrument::TOTAL_VARIANTS],
+) -> Vec<MidiMessage> {
+    match event {
+        Event::NoteOn(inst) => {
+            let note = note_map[note_index(inst)];
+            vec![
+                MidiMessage::NoteOn {
+                    channel,
+                    note,
+                    velocity: VELOCITY,
+                },
+                MidiMessage::NoteOff { channel, note },
+            ]
+        }
+        Event::PlayheadBang(Measure::Quaver) => vec![MidiMessage::Clock],
+        _ => vec![],
+    }
+}
+
+/// A MIDI message scheduled to be sent at a given instant.
+///
+/// Ordered by due time (earliest first) with a monotonic sequence number as a tiebreaker, so that
+/// messages scheduled for the same instant retain their submission order.
+struct Scheduled {
+    due: Instant,
+    seq: u64,
+    msg: MidiMessage,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        self.due == other.due && self.seq == other.seq
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.due.cmp(&other.due).then(self.seq.cmp(&other.seq))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Mirrors Jen events out of a MIDI output port.
+///
+/// The output connection lives on a dedicated timer thread. [`send`](Self::send) enqueues note-ons
+/// to go out immediately and their note-offs to go out one gate length later, returning without
+/// blocking so the caller's frame loop is never stalled. Dropping the bridge drains any pending
+/// messages and joins the thread.
+pub struct MidiBridge {
+    tx: Option<Sender<Scheduled>>,
+    thread: Option<JoinHandle<()>>,
+    seq: u64,
+    channel: u8,
+    gate: Duration,
+    note_map: [u8; Instrument::TOTAL_VARIANTS],
+}
+
+impl MidiBridge {
+    /// Open a virtual output port with the given name.
+    ///
+    /// Virtual ports are only available on platforms with ALSA or CoreMIDI support.
+    #[cfg(unix)]
+    pub fn virtual_port(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = MidiOutput::new("jen_rx")?;
+        let conn = output.create_virtual(name)?;
+        Ok(Self::with_connection(conn))
+    }
+
+    /// Build a bridge around an already-open output connection.
+    pub fn with_connection(conn: MidiOutputConnection) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let thread = thread::spawn(move || timer_loop(conn, rx));
+        MidiBridge {
+            tx: Some(tx),
+            thread: Some(thread),
+            seq: 0,
+            channel: 0,
+            gate: DEFAULT_GATE,
+            note_map: DEFAULT_NOTE_MAP,
+        }
+    }
+
+    /// Set the MIDI channel (`0..16`) that notes are sent on.
+    pub fn channel(mut self, channel: u8) -> Self {
+        self.channel = channel & 0x0F;
+        self
+    }
+
+    /// Set the gate length held between each note-on and its note-off.
+    pub fn gate(mut self, gate: Duration) -> Self {
+        self.gate = gate;
+        self
+    }
+
+    /// Set the per-instrument note mapping.
+    pub fn note_map(mut self, note_map: [u8; Instrument::TOTAL_VARIANTS]) -> Self {
+        self.note_map = note_map;
+        self
+    }
+
+    /// Translate the given events and hand them to the timer thread for output.
+    ///
+    /// Note-ons are scheduled immediately and each matching note-off one gate length later. This
+    /// returns without blocking, so a batch of note-ons never stalls the calling thread.
+    pub fn send<I>(&mut self, events: I)
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        let now = Instant::now();
+        for event in events {
+            for msg in event_to_messages(event, self.channel, &self.note_map) {
+                let due = match msg {
+                    MidiMessage::NoteOff { .. } => now + self.gate,
+                    _ => now,
+                };
+                let scheduled = Scheduled {
+                    due,
+                    seq: self.seq,
+                    msg,
+                };
+                self.seq = self.seq.wrapping_add(1);
+                // If the timer thread has gone, there is nothing left to send.
+                if let Some(tx) = &self.tx {
+                    if tx.send(scheduled).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for MidiBridge {
+    fn drop(&mut self) {
+        // Dropping the sender lets the timer thread drain its queue and exit.
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Send scheduled messages out of the connection at their due times until the sender is dropped.
+///
+/// Messages wait on a priority queue keyed by due time; the thread sleeps until the next message is
+/// due or a new one arrives, so it never busy-waits. On shutdown any remaining messages (e.g.
+/// pending note-offs) are flushed immediately so no note is left hanging.
+fn timer_loop(mut conn: MidiOutputConnection, rx: Receiver<Scheduled>) {
+    let mut queue: BinaryHeap<Reverse<Scheduled>> = BinaryHeap::new();
+    loop {
+        // Send anything that is now due.
+        let now = Instant::now();
+        while queue.peek().map_or(false, |Reverse(s)| s.due <= now) {
+            let Reverse(scheduled) = queue.pop().expect("peek guaranteed an entry");
+            if let Err(e) = conn.send(&scheduled.msg.bytes()) {
+                eprintln!("failed to send MIDI message: {}", e);
+            }
+        }
+
+        // Wait for the next due message, or for a newly submitted one, whichever is sooner.
+        let next = match queue.peek() {
+            Some(Reverse(s)) => rx.recv_timeout(s.due.saturating_duration_since(Instant::now())),
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match next {
+            Ok(scheduled) => queue.push(Reverse(scheduled)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                for Reverse(scheduled) in queue.drain() {
+                    let _ = conn.send(&scheduled.msg.bytes());
+                }
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn note_on_yields_on_off_pair() {
+        let msgs = event_to_messages(Event::NoteOn(Instrument::Kick), 0, &DEFAULT_NOTE_MAP);
+        assert_eq!(
+            msgs,
+            vec![
+                MidiMessage::NoteOn {
+                    channel: 0,
+                    note: 36,
+                    velocity: VELOCITY,
+                },
+                MidiMessage::NoteOff {
+                    channel: 0,
+                    note: 36,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn quaver_bang_yields_clock() {
+        assert_eq!(
+            event_to_messages(Event::PlayheadBang(Measure::Quaver), 0, &DEFAULT_NOTE_MAP),
+            vec![MidiMessage::Clock]
+        );
+        assert!(event_to_messages(Event::PlayheadBang(Measure::Beat), 0, &DEFAULT_NOTE_MAP).is_empty());
+    }
+
+    #[test]
+    fn message_bytes() {
+        assert_eq!(
+            MidiMessage::NoteOn {
+                channel: 1,
+                note: 60,
+                velocity: 100,
+            }
+            .bytes(),
+            vec![0x91, 60, 100]
+        );
+        assert_eq!(
+            MidiMessage::NoteOff {
+                channel: 1,
+                note: 60,
+            }
+            .bytes(),
+            vec![0x81, 60, 0]
+        );
+        assert_eq!(MidiMessage::Clock.bytes(), vec![0xF8]);
+    }
+}
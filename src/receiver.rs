@@ -0,0 +1,138 @@
+//! A background, non-blocking OSC receiver.
+//!
+//! [`OscReceiver`] binds a UDP socket and runs a dedicated thread that decodes each incoming packet
+//! into [`Event`]s and pushes them over a [`crossbeam_channel`]. Callers pull the queued events with
+//! [`State::drain`](crate::State::drain) from the render thread, decoupling network receipt from the
+//! frame loop. Dropping the receiver signals the thread to stop and joins it.
+
+use crate::{osc_msg_to_events, Event};
+use crossbeam_channel::{unbounded, Receiver, TryIter};
+use nannou::osc;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The interval at which the decode thread wakes to check for shutdown while idle.
+const RECV_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// A background OSC receiver that decodes incoming packets into [`Event`]s on its own thread.
+pub struct OscReceiver {
+    events: Receiver<(Instant, Event)>,
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscReceiver {
+    /// Bind a UDP socket to the given address and spawn the decode thread.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(RECV_TIMEOUT))?;
+        let local_addr = socket.local_addr()?;
+        let (tx, events) = unbounded();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || decode_loop(socket, tx, shutdown))
+        };
+        Ok(OscReceiver {
+            events,
+            local_addr,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the underlying socket is bound to.
+    ///
+    /// Useful when binding to port `0` to discover the OS-assigned port.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// A non-blocking iterator over all currently-queued events, each paired with the `Instant` it
+    /// was decoded at so that tempo estimation reflects real arrival times.
+    pub fn try_iter(&self) -> TryIter<(Instant, Event)> {
+        self.events.try_iter()
+    }
+}
+
+impl Drop for OscReceiver {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Decode packets from the socket into events until signalled to shut down.
+fn decode_loop(
+    socket: UdpSocket,
+    tx: crossbeam_channel::Sender<(Instant, Event)>,
+    shutdown: Arc<AtomicBool>,
+) {
+    let mut buf = [0u8; 65_536];
+    while !shutdown.load(Ordering::Relaxed) {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _addr)) => {
+                // Timestamp at decode time so the true arrival order is carried through, rather than
+                // collapsing to a single `Instant` when a frame drains several packets at once.
+                let arrived = Instant::now();
+                let packet = match osc::decoder::decode(&buf[..len]) {
+                    Ok(packet) => packet,
+                    Err(_) => continue,
+                };
+                for msg in packet.into_msgs() {
+                    for event in osc_msg_to_events(msg) {
+                        // The render thread has dropped the receiver; nothing left to do.
+                        if tx.send((arrived, event)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Instrument, State};
+    use nannou::osc::{encoder, Message, Packet, Type};
+
+    #[test]
+    fn loopback_delivers_events() {
+        let receiver = OscReceiver::bind("127.0.0.1:0").unwrap();
+
+        // A `/jen` note-on for the kick: mode `100` followed by instrument index `1`.
+        let msg = Message {
+            addr: "/jen".to_string(),
+            args: Some(vec![Type::Int(100), Type::Int(1)]),
+        };
+        let bytes = encoder::encode(&Packet::Message(msg)).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(&bytes, receiver.local_addr()).unwrap();
+
+        // The decode thread runs asynchronously, so poll briefly for the event to arrive.
+        let mut state = State::new();
+        for _ in 0..50 {
+            state.drain(&receiver);
+            if !state.note_ons.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(state.note_ons.contains_key(&Instrument::Kick));
+
+        // Dropping the receiver signals the decode thread and joins it cleanly.
+        drop(receiver);
+    }
+}
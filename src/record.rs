@@ -0,0 +1,199 @@
+//! Recording and replay of the Jen event stream.
+//!
+//! A [`Recorder`] captures each [`Event`] along with the [`Instant`] it arrived and stores it as a
+//! [`Duration`] offset from the first recorded event (as `Instant`s cannot be serialised). A
+//! [`Player`] reads such a log back and re-emits the events into [`State::update_by_events`] at the
+//! same wall-clock offsets, allowing a performance to be replayed deterministically.
+//!
+//! Logs may be persisted behind the [`Format`] trait. Two formats are provided: [`Json`], a
+//! line-delimited human-readable format useful for debugging, and [`Binary`], a compact MessagePack
+//! encoding via `rmp-serde` suited to long sessions.
+
+use crate::{Event, State};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufRead, Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A single recorded event along with its offset from the start of the recording.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The time at which the event arrived, relative to the first event in the log.
+    pub offset: Duration,
+    /// The recorded event.
+    pub event: Event,
+}
+
+/// An ordered collection of recorded events.
+pub type Log = Vec<Entry>;
+
+/// Captures the event stream as a [`Log`] of `(offset, event)` entries.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recorder {
+    start: Option<Instant>,
+    log: Log,
+}
+
+impl Recorder {
+    /// Construct an empty recorder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the given event as having arrived at `arrived`.
+    ///
+    /// The first recorded event establishes the zero offset for the recording.
+    pub fn record(&mut self, event: Event, arrived: Instant) {
+        let start = *self.start.get_or_insert(arrived);
+        let offset = arrived.saturating_duration_since(start);
+        self.log.push(Entry { offset, event });
+    }
+
+    /// The recorded log so far.
+    pub fn log(&self) -> &[Entry] {
+        &self.log
+    }
+
+    /// Consume the recorder, yielding the recorded log.
+    pub fn into_log(self) -> Log {
+        self.log
+    }
+}
+
+/// Replays a recorded [`Log`] into a [`State`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Player {
+    log: Log,
+}
+
+impl Player {
+    /// Construct a player for the given log.
+    pub fn new(log: Log) -> Self {
+        Player { log }
+    }
+
+    /// The log to be replayed.
+    pub fn log(&self) -> &[Entry] {
+        &self.log
+    }
+
+    /// Replay the log into the given state in real time, sleeping between entries so that each event
+    /// is re-emitted at its recorded offset from the start of playback.
+    pub fn play(&self, state: &mut State) {
+        let start = Instant::now();
+        for entry in &self.log {
+            let elapsed = start.elapsed();
+            if let Some(remaining) = entry.offset.checked_sub(elapsed) {
+                thread::sleep(remaining);
+            }
+            state.update_by_events(Some(entry.event));
+        }
+    }
+}
+
+/// A serialisation format for a recorded [`Log`].
+pub trait Format {
+    /// Encode the given log to the writer.
+    fn encode(&self, writer: &mut dyn Write, log: &Log) -> io::Result<()>;
+    /// Decode a log from the reader.
+    fn decode(&self, reader: &mut dyn Read) -> io::Result<Log>;
+}
+
+/// A line-delimited JSON format, with one [`Entry`] per line.
+///
+/// Intended for debugging, where a log can be inspected or edited by hand.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Json;
+
+impl Format for Json {
+    fn encode(&self, writer: &mut dyn Write, log: &Log) -> io::Result<()> {
+        for entry in log {
+            let line = serde_json::to_string(entry)?;
+            writeln!(writer, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> io::Result<Log> {
+        let mut log = Log::new();
+        for line in io::BufReader::new(reader).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            log.push(serde_json::from_str(&line)?);
+        }
+        Ok(log)
+    }
+}
+
+/// A compact MessagePack format via `rmp-serde`.
+///
+/// Intended for long sessions where the line-delimited JSON format would be wasteful.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Binary;
+
+impl Format for Binary {
+    fn encode(&self, writer: &mut dyn Write, log: &Log) -> io::Result<()> {
+        let bytes = rmp_serde::to_vec(log)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        writer.write_all(&bytes)
+    }
+
+    fn decode(&self, reader: &mut dyn Read) -> io::Result<Log> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        rmp_serde::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Event, Instrument, Measure};
+
+    /// A short log with one entry of each event kind at increasing offsets.
+    fn sample_log() -> Log {
+        let start = Instant::now();
+        let mut rec = Recorder::new();
+        rec.record(Event::NoteOn(Instrument::Kick), start);
+        rec.record(
+            Event::PlayheadBang(Measure::Beat),
+            start + Duration::from_millis(5),
+        );
+        rec.record(
+            Event::PlayheadPosition(Measure::Bar, 0.5),
+            start + Duration::from_millis(10),
+        );
+        rec.into_log()
+    }
+
+    /// Encode and decode the log through the given format.
+    fn round_trip<F: Format>(fmt: &F, log: &Log) -> Log {
+        let mut buf = Vec::new();
+        fmt.encode(&mut buf, log).unwrap();
+        fmt.decode(&mut &buf[..]).unwrap()
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let log = sample_log();
+        assert_eq!(round_trip(&Json, &log), log);
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let log = sample_log();
+        assert_eq!(round_trip(&Binary, &log), log);
+    }
+
+    #[test]
+    fn player_drives_state_transitions() {
+        let log = sample_log();
+        let mut state = State::new();
+        Player::new(log).play(&mut state);
+        assert!(state.note_ons.contains_key(&Instrument::Kick));
+        assert!(state.playhead_bangs.contains_key(&Measure::Beat));
+        assert_eq!(state.playhead_position(Measure::Bar), Some(0.5));
+    }
+}